@@ -1,14 +1,19 @@
 use argh::FromArgs;
 use image::imageops::{overlay, FilterType};
+use rayon::prelude::*;
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 use std::{
+    collections::HashMap,
     collections::HashSet,
     fmt::Display,
     fs,
+    hash::{Hash, Hasher},
     io::Write,
     path::Path,
     path::PathBuf,
     process::{self, Command},
+    sync::Mutex,
 };
 use walkdir::WalkDir;
 use yansi::Paint;
@@ -19,22 +24,91 @@ const REPORT_PATH: &str = "./imdirdiff-out";
 const THUMB_WIDTH: u32 = u32::MAX;
 const THUMB_HEIGHT: u32 = 80;
 const THUMB_EXTENSION: &str = "sm.jpg";
+const CACHE_FILE: &str = ".imdirdiff-cache.json";
 
-static RE_FLIP: once_cell::sync::Lazy<Regex> =
-    once_cell::sync::Lazy::new(|| Regex::new(r"Mean: ([\d.]+)").unwrap());
+const EXIT_DIFFERENCES_FOUND: i32 = 1;
+const EXIT_ERROR: i32 = 2;
 
 #[derive(FromArgs)]
 /// Reach new heights.
 struct Args {
-    /// use nvidia's flip (https://github.com/NVlabs/flip) instead of image_compare
+    /// use nvidia's flip (https://github.com/NVlabs/flip) instead of image_compare; a
+    /// shorthand for the equivalent --external-* options
     #[argh(switch)]
     flip: bool,
+    /// path to an external comparator executable to use instead of image_compare; run
+    /// with --external-arg and its score parsed with --external-regex
+    #[argh(option)]
+    external_cmd: Option<String>,
+    /// argument to pass to --external-cmd (can be repeated, in order); may contain the
+    /// placeholders {a}, {b}, {diff_dir}, and {diff_basename}
+    #[argh(option)]
+    external_arg: Vec<String>,
+    /// regex with one capture group used to pull the similarity/error score out of
+    /// --external-cmd's stdout
+    #[argh(option, default = "String::from(\"Mean: ([\\\\d.]+)\")")]
+    external_regex: String,
+    /// treat --external-regex's captured score as an error (0.0 = identical) and invert
+    /// it into a similarity
+    #[argh(switch)]
+    external_invert_score: bool,
+    /// always exit 0, even if differences are found
+    #[argh(switch)]
+    exit_zero: bool,
+    /// minimum similarity (0.0-1.0) for two images to be considered the same
+    #[argh(option, default = "1.0")]
+    threshold: f64,
+    /// comparison metric to use: hybrid (default), mse, or psnr
+    #[argh(option, default = "Metric::Hybrid")]
+    metric: Metric,
+    /// only compare relative paths matching this glob (can be repeated); if none are given, everything matches
+    #[argh(option)]
+    include: Vec<String>,
+    /// skip relative paths matching this glob (can be repeated); takes precedence over --include
+    #[argh(option)]
+    exclude: Vec<String>,
+    /// skip decoding and comparing files whose content hash is unchanged since the last run
+    #[argh(switch)]
+    cache: bool,
+    /// disable the content hash cache even if --cache is given
+    #[argh(switch)]
+    no_cache: bool,
     #[argh(positional)]
     a: String,
     #[argh(positional)]
     b: String,
 }
 
+#[derive(Clone, Copy)]
+enum Metric {
+    Hybrid,
+    Mse,
+    Psnr,
+}
+
+impl Metric {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Hybrid => "hybrid",
+            Self::Mse => "mse",
+            Self::Psnr => "psnr",
+        }
+    }
+}
+
+impl std::str::FromStr for Metric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hybrid" => Ok(Self::Hybrid),
+            "mse" => Ok(Self::Mse),
+            "psnr" => Ok(Self::Psnr),
+            _ => Err(format!("unknown metric: {s}")),
+        }
+    }
+}
+
 enum Diff {
     OnlyInA,
     OnlyInB,
@@ -46,6 +120,21 @@ struct DiffResult {
     path: PathBuf,
 }
 
+fn diff_result_for_similarity(
+    subpath: &Path,
+    similarity: f64,
+    threshold: f64,
+) -> Option<DiffResult> {
+    if similarity < threshold {
+        Some(DiffResult {
+            diff: Diff::Different { similarity },
+            path: subpath.to_path_buf(),
+        })
+    } else {
+        None
+    }
+}
+
 fn main() {
     let args: Args = argh::from_env();
 
@@ -54,20 +143,44 @@ fn main() {
 
     if let Err(e) = check_dir(&path_a) {
         eprintln!("Error reading {}: {}", path_a.display(), e);
-        process::exit(1);
+        process::exit(EXIT_ERROR);
     }
 
     if let Err(e) = check_dir(&path_b) {
         eprintln!("Error reading {}: {}", path_b.display(), e);
-        process::exit(1);
+        process::exit(EXIT_ERROR);
     }
 
-    let images_a = relative_image_paths(&path_a);
-    let images_b = relative_image_paths(&path_b);
+    let filter = match PathFilter::new(&args.include, &args.exclude) {
+        Err(e) => {
+            eprintln!("Error compiling --include/--exclude globs: {e}");
+            process::exit(EXIT_ERROR);
+        }
+        Ok(filter) => filter,
+    };
+
+    let external = match build_external_comparator(&args) {
+        Err(e) => {
+            eprintln!("Error configuring external comparator: {e}");
+            process::exit(EXIT_ERROR);
+        }
+        Ok(external) => external,
+    };
+
+    let images_a = relative_image_paths(&path_a, &filter);
+    let images_b = relative_image_paths(&path_b, &filter);
 
     let mut results = vec![];
 
     for subpath in images_a.difference(&images_b) {
+        if GENERATE_REPORT {
+            let image_path: PathBuf = [&path_a, subpath.as_path()].iter().collect();
+            if let Err(e) = copy_report_image(&image_path, subpath, Path::new("a")) {
+                eprintln!("Error copying report image {}: {}", subpath.display(), e);
+                process::exit(EXIT_ERROR);
+            }
+        }
+
         let result = DiffResult {
             path: subpath.clone(),
             diff: Diff::OnlyInA,
@@ -77,6 +190,14 @@ fn main() {
     }
 
     for subpath in images_b.difference(&images_a) {
+        if GENERATE_REPORT {
+            let image_path: PathBuf = [&path_b, subpath.as_path()].iter().collect();
+            if let Err(e) = copy_report_image(&image_path, subpath, Path::new("b")) {
+                eprintln!("Error copying report image {}: {}", subpath.display(), e);
+                process::exit(EXIT_ERROR);
+            }
+        }
+
         let result = DiffResult {
             path: subpath.clone(),
             diff: Diff::OnlyInB,
@@ -85,34 +206,126 @@ fn main() {
         results.push(result);
     }
 
-    for subpath in images_a.intersection(&images_b) {
-        let similarity = if args.flip {
-            compare_flip(&path_a, &path_b, subpath)
-        } else {
-            compare(&path_a, &path_b, subpath)
-        };
+    let intersection: Vec<PathBuf> = images_a.intersection(&images_b).cloned().collect();
+    let errors = Mutex::new(Vec::new());
 
-        let similarity = match similarity {
-            Err(e) => {
-                eprintln!("Error comparing {} {}", subpath.display(), e);
-                process::exit(1);
+    let cache_enabled = args.cache && !args.no_cache;
+    let cache_path: PathBuf = [Path::new(REPORT_PATH), Path::new(CACHE_FILE)]
+        .iter()
+        .collect();
+    let cache_params = CacheParams::new(&args, external.as_ref());
+    let previous_cache = if cache_enabled {
+        load_cache(&cache_path, &cache_params)
+    } else {
+        Cache::default()
+    };
+    let next_cache = Mutex::new(Cache {
+        params: cache_params,
+        entries: HashMap::new(),
+    });
+
+    let mut intersection_results: Vec<DiffResult> = intersection
+        .par_iter()
+        .filter_map(|subpath| {
+            let image_path_a: PathBuf = [&path_a, subpath.as_path()].iter().collect();
+            let image_path_b: PathBuf = [&path_b, subpath.as_path()].iter().collect();
+
+            let hashes = cache_enabled
+                .then(|| {
+                    file_hash(&image_path_a)
+                        .ok()
+                        .zip(file_hash(&image_path_b).ok())
+                })
+                .flatten();
+
+            if let Some((hash_a, hash_b)) = hashes {
+                let cached = previous_cache
+                    .entries
+                    .get(subpath)
+                    .filter(|e| e.hash_a == hash_a && e.hash_b == hash_b);
+
+                if let Some(cached) = cached {
+                    let similarity = cached.similarity;
+                    next_cache.lock().unwrap().entries.insert(
+                        subpath.clone(),
+                        CacheEntry {
+                            hash_a,
+                            hash_b,
+                            similarity,
+                        },
+                    );
+
+                    return diff_result_for_similarity(subpath, similarity, args.threshold);
+                }
             }
-            Ok(r) => r,
-        };
 
-        if similarity < 1.0 {
-            let result = DiffResult {
-                diff: Diff::Different { similarity },
-                path: subpath.clone(),
+            let similarity = if let Some(comparator) = &external {
+                compare_external(&path_a, &path_b, subpath, comparator)
+            } else {
+                compare(&path_a, &path_b, subpath, args.metric)
+            };
+
+            let similarity = match similarity {
+                Err(e) => {
+                    errors.lock().unwrap().push(format!(
+                        "Error comparing {} {}",
+                        subpath.display(),
+                        e
+                    ));
+                    return None;
+                }
+                Ok(r) => r,
             };
-            print_result(&result);
-            results.push(result);
+
+            if let Some((hash_a, hash_b)) = hashes {
+                next_cache.lock().unwrap().entries.insert(
+                    subpath.clone(),
+                    CacheEntry {
+                        hash_a,
+                        hash_b,
+                        similarity,
+                    },
+                );
+            }
+
+            diff_result_for_similarity(subpath, similarity, args.threshold)
+        })
+        .collect();
+
+    let errors = errors.into_inner().unwrap();
+    if !errors.is_empty() {
+        for error in &errors {
+            eprintln!("{error}");
         }
+        process::exit(EXIT_ERROR);
     }
 
-    if let Err(e) = generate_report(&results) {
+    if cache_enabled {
+        if let Err(e) = save_cache(&cache_path, &next_cache.into_inner().unwrap()) {
+            eprintln!("Error writing cache: {e}");
+        }
+    }
+
+    intersection_results.sort_by(|a, b| a.path.cmp(&b.path));
+    for result in &intersection_results {
+        print_result(result);
+    }
+    results.extend(intersection_results);
+
+    if let Err(e) = generate_report(&results, args.threshold, args.metric) {
         eprintln!("Error generating report: {}", e);
-        process::exit(1);
+        process::exit(EXIT_ERROR);
+    }
+
+    let differences_found = results.iter().any(|r| {
+        matches!(
+            r.diff,
+            Diff::OnlyInA | Diff::OnlyInB | Diff::Different { .. }
+        )
+    });
+
+    if differences_found && !args.exit_zero {
+        process::exit(EXIT_DIFFERENCES_FOUND);
     }
 }
 
@@ -124,32 +337,47 @@ fn print_result(result: &DiffResult) {
         Diff::OnlyInB => {
             println!("[{}] {}", Paint::green("+"), result.path.display());
         }
-        Diff::Different {
-            similarity: _similarity,
-        } => {
-            println!("[{}] {}", Paint::yellow("≠"), result.path.display());
+        Diff::Different { similarity } => {
+            println!(
+                "[{}] {} ({:.4})",
+                Paint::yellow("≠"),
+                result.path.display(),
+                similarity
+            );
         }
     }
 }
 
+fn ensure_dir(path: &Path) -> Result<(), std::io::Error> {
+    match fs::create_dir_all(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
 fn copy_report_image(path: &Path, subpath: &Path, prefix: &Path) -> Result<(), ImDirDiffError> {
     let report_image: PathBuf = [Path::new(REPORT_PATH), prefix, subpath].iter().collect();
 
-    fs::create_dir_all(report_image.with_file_name("")).map_err(ImDirDiffError::ReportIoError)?;
+    ensure_dir(&report_image.with_file_name("")).map_err(ImDirDiffError::ReportIoError)?;
     fs::copy(path, &report_image).map_err(ImDirDiffError::ReportIoError)?;
 
     let thumb_path = report_image.with_extension(THUMB_EXTENSION);
 
     let image = image::open(report_image).map_err(ImDirDiffError::ReportImageError)?;
-    image.resize(u32::MAX, 80, FilterType::Triangle);
-    image
+    let thumb = image.resize(THUMB_WIDTH, THUMB_HEIGHT, FilterType::Triangle);
+    thumb
         .save(thumb_path)
         .map_err(ImDirDiffError::ReportImageError)?;
 
     Ok(())
 }
 
-fn generate_report(results: &Vec<DiffResult>) -> Result<(), ImDirDiffError> {
+fn generate_report(
+    results: &Vec<DiffResult>,
+    threshold: f64,
+    metric: Metric,
+) -> Result<(), ImDirDiffError> {
     let index_path: PathBuf = [PathBuf::from(REPORT_PATH), "index.html".into()]
         .iter()
         .collect();
@@ -167,37 +395,91 @@ fn generate_report(results: &Vec<DiffResult>) -> Result<(), ImDirDiffError> {
     )
     .map_err(ImDirDiffError::ReportIoError)?;
 
-    write!(&mut report, "<div>").map_err(ImDirDiffError::ReportIoError)?;
+    write_summary(&mut report, results, threshold, metric)?;
+    write_only_in_gallery(&mut report, results)?;
+    write_diff_gallery(&mut report, results)?;
+
+    write!(
+        &mut report,
+        "<script>{}</script>",
+        include_str!("../templates/script.js")
+    )
+    .map_err(ImDirDiffError::ReportIoError)?;
+
+    Ok(())
+}
+
+fn write_summary(
+    report: &mut impl Write,
+    results: &[DiffResult],
+    threshold: f64,
+    metric: Metric,
+) -> Result<(), ImDirDiffError> {
+    let added = results
+        .iter()
+        .filter(|r| matches!(r.diff, Diff::OnlyInB))
+        .count();
+    let removed = results
+        .iter()
+        .filter(|r| matches!(r.diff, Diff::OnlyInA))
+        .count();
+    let changed = results
+        .iter()
+        .filter(|r| matches!(r.diff, Diff::Different { .. }))
+        .count();
+
+    write!(
+        report,
+        "<div class=\"summary\">
+            <span class=\"added\">{added} added</span>
+            <span class=\"removed\">{removed} removed</span>
+            <span class=\"changed\">{changed} changed</span>
+            <span class=\"metric\">metric: {}</span>
+            <span class=\"threshold\">threshold: {threshold:.4}</span>
+        </div>",
+        metric.as_str(),
+    )
+    .map_err(ImDirDiffError::ReportIoError)
+}
+
+fn write_only_in_gallery(
+    report: &mut impl Write,
+    results: &[DiffResult],
+) -> Result<(), ImDirDiffError> {
+    write!(report, "<div class=\"gallery\">").map_err(ImDirDiffError::ReportIoError)?;
 
     for result in results {
-        match result.diff {
-            Diff::OnlyInA => {
-                write!(
-                    &mut report,
-                    "<div>{} is only present in A</div>",
-                    result.path.display()
-                )
-                .map_err(ImDirDiffError::ReportIoError)?;
-            }
-            Diff::OnlyInB => {
-                write!(
-                    &mut report,
-                    "<div>{} is only present in B</div>",
-                    result.path.display()
-                )
-                .map_err(ImDirDiffError::ReportIoError)?;
-            }
-            _ => {}
-        }
+        let (prefix, badge) = match result.diff {
+            Diff::OnlyInA => ("a", "removed"),
+            Diff::OnlyInB => ("b", "added"),
+            Diff::Different { .. } => continue,
+        };
+
+        let thumb = result.path.with_extension(THUMB_EXTENSION);
+        let thumb = thumb.display();
+        let full_size = result.path.display();
+
+        write!(
+            report,
+            "<div class=\"only-in\">
+                {full_size} <span class=\"badge {badge}\">{badge}</span>
+                <a href=\"{prefix}/{full_size}\"><img loading=\"lazy\" src=\"{prefix}/{thumb}\"></a>
+            </div>",
+        )
+        .map_err(ImDirDiffError::ReportIoError)?;
     }
 
-    write!(&mut report, "</div><div class=\"diffs\">").map_err(ImDirDiffError::ReportIoError)?;
+    write!(report, "</div>").map_err(ImDirDiffError::ReportIoError)
+}
+
+fn write_diff_gallery(
+    report: &mut impl Write,
+    results: &[DiffResult],
+) -> Result<(), ImDirDiffError> {
+    write!(report, "<div class=\"diffs\">").map_err(ImDirDiffError::ReportIoError)?;
 
     for result in results {
-        let Diff::Different {
-            similarity: _similarity,
-        } = result.diff
-        else {
+        let Diff::Different { similarity } = result.diff else {
             continue;
         };
 
@@ -206,9 +488,10 @@ fn generate_report(results: &Vec<DiffResult>) -> Result<(), ImDirDiffError> {
         let full_size = result.path.display();
 
         write!(
-            &mut report,
+            report,
             "<div class=\"diff\">
                 {full_size} <span class=\"x\">x</span>
+                <span class=\"score\">{similarity:.4}</span>
                 <div>
                     <a href=\"a/{full_size}\"><img loading=\"lazy\" src=\"a/{thumb}\"></a>
                     <a href=\"b/{full_size}\"><img loading=\"lazy\" src=\"b/{thumb}\"></a>
@@ -219,19 +502,33 @@ fn generate_report(results: &Vec<DiffResult>) -> Result<(), ImDirDiffError> {
         .map_err(ImDirDiffError::ReportIoError)?;
     }
 
-    write!(&mut report, "</div>").map_err(ImDirDiffError::ReportIoError)?;
+    write!(report, "</div>").map_err(ImDirDiffError::ReportIoError)
+}
 
-    write!(
-        &mut report,
-        "<script>{}</script>",
-        include_str!("../templates/script.js")
-    )
-    .map_err(ImDirDiffError::ReportIoError)?;
+// 255^2, normalizes MSE into the crate's 0.0..=1.0 similarity convention.
+const MSE_NORMALIZATION: f64 = 65025.0;
+// PSNR (dB) above which two images are considered perceptually identical.
+const PSNR_NORMALIZATION: f64 = 50.0;
 
-    Ok(())
+fn mse_similarity(mse: f64) -> f64 {
+    1.0 - mse / MSE_NORMALIZATION
+}
+
+fn psnr_similarity(mse: f64) -> f64 {
+    let psnr = if mse == 0.0 {
+        f64::INFINITY
+    } else {
+        20.0 * 255f64.log10() - 10.0 * mse.log10()
+    };
+    (psnr / PSNR_NORMALIZATION).clamp(0.0, 1.0)
 }
 
-fn compare(path_a: &Path, path_b: &Path, subpath: &Path) -> Result<f64, ImDirDiffError> {
+fn compare(
+    path_a: &Path,
+    path_b: &Path,
+    subpath: &Path,
+    metric: Metric,
+) -> Result<f64, ImDirDiffError> {
     let image_path_a: PathBuf = [path_a, subpath].iter().collect();
     let image_path_b: PathBuf = [path_b, subpath].iter().collect();
 
@@ -243,7 +540,7 @@ fn compare(path_a: &Path, path_b: &Path, subpath: &Path) -> Result<f64, ImDirDif
         .map_err(ImDirDiffError::ImageError)?
         .into_rgb8();
 
-    let similarity = if image_a.dimensions() != image_b.dimensions() {
+    let (image_a, image_b) = if image_a.dimensions() != image_b.dimensions() {
         let max_width = image_a.width().max(image_b.width());
         let max_height = image_a.height().max(image_b.height());
 
@@ -252,11 +549,25 @@ fn compare(path_a: &Path, path_b: &Path, subpath: &Path) -> Result<f64, ImDirDif
         let mut enlarged_b = image::ImageBuffer::new(max_width, max_height);
         overlay(&mut enlarged_b, &image_b, 0, 0);
 
-        image_compare::rgb_hybrid_compare(&enlarged_a, &enlarged_b)
-            .map_err(ImDirDiffError::CompareError)?
+        (enlarged_a, enlarged_b)
     } else {
-        image_compare::rgb_hybrid_compare(&image_a, &image_b)
-            .map_err(ImDirDiffError::CompareError)?
+        (image_a, image_b)
+    };
+
+    let (score, diff_image) = match metric {
+        Metric::Hybrid => {
+            let similarity = image_compare::rgb_hybrid_compare(&image_a, &image_b)
+                .map_err(ImDirDiffError::CompareError)?;
+            (similarity.score, similarity.image.to_color_map())
+        }
+        Metric::Mse => {
+            let mse = mean_squared_error(&image_a, &image_b);
+            (mse_similarity(mse), abs_diff_image(&image_a, &image_b))
+        }
+        Metric::Psnr => {
+            let mse = mean_squared_error(&image_a, &image_b);
+            (psnr_similarity(mse), abs_diff_image(&image_a, &image_b))
+        }
     };
 
     if GENERATE_REPORT {
@@ -267,24 +578,118 @@ fn compare(path_a: &Path, path_b: &Path, subpath: &Path) -> Result<f64, ImDirDif
             .iter()
             .collect();
 
-        fs::create_dir_all(image_path_diff.with_file_name(""))
-            .map_err(ImDirDiffError::ReportIoError)?;
+        ensure_dir(&image_path_diff.with_file_name("")).map_err(ImDirDiffError::ReportIoError)?;
 
-        let color_map = similarity.image.to_color_map();
-        color_map
+        diff_image
             .save(&image_path_diff)
             .map_err(ImDirDiffError::ReportImageError)?;
 
-        color_map
+        diff_image
             .resize(THUMB_WIDTH, THUMB_HEIGHT, FilterType::Triangle)
             .save(image_path_diff.with_extension(THUMB_EXTENSION))
             .map_err(ImDirDiffError::ReportImageError)?;
     }
 
-    Ok(similarity.score)
+    Ok(score)
+}
+
+fn mean_squared_error(image_a: &image::RgbImage, image_b: &image::RgbImage) -> f64 {
+    let (width, height) = image_a.dimensions();
+
+    let sum_squared_error: f64 = image_a
+        .pixels()
+        .zip(image_b.pixels())
+        .flat_map(|(a, b)| a.0.iter().zip(b.0.iter()))
+        .map(|(&a, &b)| {
+            let diff = a as f64 - b as f64;
+            diff * diff
+        })
+        .sum();
+
+    sum_squared_error / (width as f64 * height as f64 * 3.0)
+}
+
+fn abs_diff_image(image_a: &image::RgbImage, image_b: &image::RgbImage) -> image::RgbImage {
+    image::ImageBuffer::from_fn(image_a.width(), image_a.height(), |x, y| {
+        let a = image_a.get_pixel(x, y);
+        let b = image_b.get_pixel(x, y);
+        image::Rgb([
+            a[0].abs_diff(b[0]),
+            a[1].abs_diff(b[1]),
+            a[2].abs_diff(b[2]),
+        ])
+    })
+}
+
+struct ExternalComparator {
+    program: String,
+    args: Vec<String>,
+    score_regex: Regex,
+    invert_score: bool,
+}
+
+impl ExternalComparator {
+    fn flip() -> Self {
+        Self {
+            program: "flip".into(),
+            args: [
+                "-r",
+                "{a}",
+                "-t",
+                "{b}",
+                "-d",
+                "{diff_dir}",
+                "-b",
+                "{diff_basename}",
+            ]
+            .map(String::from)
+            .into(),
+            score_regex: Regex::new(r"Mean: ([\d.]+)").unwrap(),
+            invert_score: true,
+        }
+    }
+}
+
+fn build_external_comparator(args: &Args) -> Result<Option<ExternalComparator>, ImDirDiffError> {
+    if args.flip {
+        return Ok(Some(ExternalComparator::flip()));
+    }
+
+    let Some(program) = &args.external_cmd else {
+        return Ok(None);
+    };
+
+    let score_regex =
+        Regex::new(&args.external_regex).map_err(ImDirDiffError::ExternalRegexError)?;
+
+    Ok(Some(ExternalComparator {
+        program: program.clone(),
+        args: args.external_arg.clone(),
+        score_regex,
+        invert_score: args.external_invert_score,
+    }))
 }
 
-fn compare_flip(path_a: &Path, path_b: &Path, subpath: &Path) -> Result<f64, ImDirDiffError> {
+fn substitute_placeholders(
+    template: &str,
+    image_path_a: &Path,
+    image_path_b: &Path,
+    diff_dir: &Path,
+    diff_basename: &str,
+) -> String {
+    template
+        .replace("{a}", &image_path_a.to_string_lossy())
+        .replace("{b}", &image_path_b.to_string_lossy())
+        .replace("{diff_dir}", &diff_dir.to_string_lossy())
+        .replace("{diff_basename}", diff_basename)
+}
+
+fn compare_external(
+    path_a: &Path,
+    path_b: &Path,
+    subpath: &Path,
+    comparator: &ExternalComparator,
+) -> Result<f64, ImDirDiffError> {
     let image_path_a: PathBuf = [path_a, subpath].iter().collect();
     let image_path_b: PathBuf = [path_b, subpath].iter().collect();
 
@@ -296,41 +701,56 @@ fn compare_flip(path_a: &Path, path_b: &Path, subpath: &Path) -> Result<f64, ImD
     .iter()
     .collect();
 
+    let diff_basename = subpath
+        .with_extension("")
+        .file_name()
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string();
+
     // TODO I think it is possible to disable diff image saving if we are not generating
     // reports.
 
-    let output = Command::new("flip")
-        .args([
-            "-r",
-            image_path_a.to_str().unwrap(),
-            "-t",
-            image_path_b.to_str().unwrap(),
-            "-d",
-            image_diff_dir.to_str().unwrap(),
-            "-b",
-            subpath
-                .with_extension("")
-                .file_name()
-                .unwrap()
-                .to_str()
-                .unwrap(),
-        ])
+    let args: Vec<String> = comparator
+        .args
+        .iter()
+        .map(|arg| {
+            substitute_placeholders(
+                arg,
+                &image_path_a,
+                &image_path_b,
+                &image_diff_dir,
+                &diff_basename,
+            )
+        })
+        .collect();
+
+    let output = Command::new(&comparator.program)
+        .args(&args)
         .output()
-        .map_err(|_| ImDirDiffError::FlipError)?;
+        .map_err(|_| ImDirDiffError::ExternalComparatorError)?;
 
-    let stdout =
-        String::from_utf8(output.stdout).map_err(|_| ImDirDiffError::FlipOutputParseError)?;
+    let stdout = String::from_utf8(output.stdout)
+        .map_err(|_| ImDirDiffError::ExternalComparatorOutputParseError)?;
 
-    let caps = RE_FLIP
+    let caps = comparator
+        .score_regex
         .captures(&stdout)
-        .ok_or(ImDirDiffError::FlipOutputParseError)?;
+        .ok_or(ImDirDiffError::ExternalComparatorOutputParseError)?;
 
-    let similarity: f64 = caps
+    let score: f64 = caps
         .get(1)
-        .ok_or(ImDirDiffError::FlipOutputParseError)?
+        .ok_or(ImDirDiffError::ExternalComparatorOutputParseError)?
         .as_str()
         .parse()
-        .map_err(|_| ImDirDiffError::FlipOutputParseError)?;
+        .map_err(|_| ImDirDiffError::ExternalComparatorOutputParseError)?;
+
+    let similarity = if comparator.invert_score {
+        1.0 - score
+    } else {
+        score
+    };
 
     if GENERATE_REPORT {
         copy_report_image(&image_path_a, subpath, Path::new("a"))?;
@@ -348,12 +768,10 @@ fn compare_flip(path_a: &Path, path_b: &Path, subpath: &Path) -> Result<f64, ImD
             .map_err(ImDirDiffError::ReportImageError)?;
     }
 
-    // TODO is this right? 0.0 is definitely "they are the same" but
-    // I don't know what the maximum value is.
-    Ok(1.0 - similarity)
+    Ok(similarity)
 }
 
-fn relative_image_paths(dir_path: &Path) -> HashSet<PathBuf> {
+fn relative_image_paths(dir_path: &Path, filter: &PathFilter) -> HashSet<PathBuf> {
     WalkDir::new(dir_path)
         .follow_links(true)
         .into_iter()
@@ -369,11 +787,129 @@ fn relative_image_paths(dir_path: &Path) -> HashSet<PathBuf> {
 
             let relative = path.to_owned().strip_prefix(dir_path).unwrap().to_owned();
 
+            if !filter.matches(&relative) {
+                return None;
+            }
+
             Some(relative)
         })
         .collect()
 }
 
+struct PathFilter {
+    include: Option<globset::GlobSet>,
+    exclude: globset::GlobSet,
+}
+
+impl PathFilter {
+    fn new(include: &[String], exclude: &[String]) -> Result<Self, ImDirDiffError> {
+        let include = if include.is_empty() {
+            None
+        } else {
+            Some(build_glob_set(include)?)
+        };
+        let exclude = build_glob_set(exclude)?;
+
+        Ok(Self { include, exclude })
+    }
+
+    fn matches(&self, relative_path: &Path) -> bool {
+        if self.exclude.is_match(relative_path) {
+            return false;
+        }
+
+        match &self.include {
+            Some(include) => include.is_match(relative_path),
+            None => true,
+        }
+    }
+}
+
+fn build_glob_set(patterns: &[String]) -> Result<globset::GlobSet, ImDirDiffError> {
+    let mut builder = globset::GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = globset::Glob::new(pattern).map_err(ImDirDiffError::GlobError)?;
+        builder.add(glob);
+    }
+    builder.build().map_err(ImDirDiffError::GlobError)
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Cache {
+    #[serde(default)]
+    params: CacheParams,
+    entries: HashMap<PathBuf, CacheEntry>,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct CacheEntry {
+    hash_a: u64,
+    hash_b: u64,
+    similarity: f64,
+}
+
+// Only valid for the args a cached similarity was computed under; load_cache
+// discards entries if these don't match the current run.
+#[derive(Default, Clone, PartialEq, Serialize, Deserialize)]
+struct CacheParams {
+    threshold: String,
+    metric: String,
+    comparator: Option<CacheComparatorParams>,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+struct CacheComparatorParams {
+    program: String,
+    args: Vec<String>,
+    score_regex: String,
+    invert_score: bool,
+}
+
+impl CacheParams {
+    fn new(args: &Args, external: Option<&ExternalComparator>) -> Self {
+        Self {
+            // Compared as the formatted string so two runs of the same
+            // `--threshold` always match, regardless of float rounding
+            // introduced by a JSON round-trip.
+            threshold: args.threshold.to_string(),
+            metric: args.metric.as_str().to_string(),
+            comparator: external.map(|c| CacheComparatorParams {
+                program: c.program.clone(),
+                args: c.args.clone(),
+                score_regex: c.score_regex.as_str().to_string(),
+                invert_score: c.invert_score,
+            }),
+        }
+    }
+}
+
+fn load_cache(cache_path: &Path, params: &CacheParams) -> Cache {
+    let cache: Cache = fs::read_to_string(cache_path)
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default();
+
+    if &cache.params != params {
+        return Cache::default();
+    }
+
+    cache
+}
+
+fn save_cache(cache_path: &Path, cache: &Cache) -> Result<(), ImDirDiffError> {
+    ensure_dir(&cache_path.with_file_name("")).map_err(ImDirDiffError::ReportIoError)?;
+
+    let contents = serde_json::to_string(cache).map_err(ImDirDiffError::CacheSerdeError)?;
+    fs::write(cache_path, contents).map_err(ImDirDiffError::ReportIoError)
+}
+
+fn file_hash(path: &Path) -> Result<u64, ImDirDiffError> {
+    let bytes = fs::read(path).map_err(ImDirDiffError::DirIoError)?;
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    Ok(hasher.finish())
+}
+
 fn check_dir(dir_path: &Path) -> Result<(), ImDirDiffError> {
     let meta = fs::metadata(dir_path).map_err(ImDirDiffError::DirIoError)?;
     if !meta.is_dir() {
@@ -388,10 +924,13 @@ enum ImDirDiffError {
     DirIoError(std::io::Error),
     ImageError(image::ImageError),
     CompareError(image_compare::CompareError),
-    FlipError,
-    FlipOutputParseError,
+    ExternalComparatorError,
+    ExternalComparatorOutputParseError,
+    ExternalRegexError(regex::Error),
     ReportIoError(std::io::Error),
     ReportImageError(image::ImageError),
+    GlobError(globset::Error),
+    CacheSerdeError(serde_json::Error),
 }
 
 impl Display for ImDirDiffError {
@@ -401,10 +940,115 @@ impl Display for ImDirDiffError {
             Self::DirIoError(ref e) => write!(f, "{}", e),
             Self::ImageError(ref e) => write!(f, "{}", e),
             Self::CompareError(ref e) => write!(f, "{}", e),
-            Self::FlipError => write!(f, "Error running flip."),
-            Self::FlipOutputParseError => write!(f, "Error parsing flip output."),
+            Self::ExternalComparatorError => write!(f, "Error running external comparator."),
+            Self::ExternalComparatorOutputParseError => {
+                write!(f, "Error parsing external comparator output.")
+            }
+            Self::ExternalRegexError(ref e) => write!(f, "{}", e),
             Self::ReportIoError(ref e) => write!(f, "{}", e),
             Self::ReportImageError(ref e) => write!(f, "{}", e),
+            Self::GlobError(ref e) => write!(f, "{}", e),
+            Self::CacheSerdeError(ref e) => write!(f, "{}", e),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_hit_on_previously_different_pair_still_reports_different() {
+        // A pair whose hashes are unchanged since the last run must still be
+        // reported as different if it was different last time; "hash
+        // unchanged" is not the same as "identical".
+        let similarity = 0.5;
+        let result = diff_result_for_similarity(Path::new("a.png"), similarity, 1.0);
+
+        match result {
+            Some(DiffResult {
+                diff: Diff::Different { similarity: s },
+                ..
+            }) => assert_eq!(s, similarity),
+            _ => panic!("expected a Different result to survive the cache hit"),
+        }
+    }
+
+    #[test]
+    fn cache_hit_above_threshold_reports_no_difference() {
+        let result = diff_result_for_similarity(Path::new("a.png"), 0.99, 0.9);
+        assert!(result.is_none());
+    }
+
+    #[test]
+    fn cache_is_ignored_when_threshold_changes() {
+        let dir = std::env::temp_dir().join("imdirdiff-test-cache-threshold");
+        fs::create_dir_all(&dir).unwrap();
+        let cache_path = dir.join(CACHE_FILE);
+
+        let old_params = CacheParams {
+            threshold: "1".to_string(),
+            metric: "hybrid".to_string(),
+            comparator: None,
+        };
+        let mut cache = Cache {
+            params: old_params,
+            entries: HashMap::new(),
+        };
+        cache.entries.insert(
+            PathBuf::from("a.png"),
+            CacheEntry {
+                hash_a: 1,
+                hash_b: 2,
+                similarity: 0.5,
+            },
+        );
+        save_cache(&cache_path, &cache).unwrap();
+
+        let new_params = CacheParams {
+            threshold: "0.9".to_string(),
+            metric: "hybrid".to_string(),
+            comparator: None,
+        };
+        let loaded = load_cache(&cache_path, &new_params);
+        assert!(loaded.entries.is_empty());
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn mean_squared_error_of_identical_images_is_zero() {
+        let image = image::ImageBuffer::from_pixel(2, 2, image::Rgb([10, 20, 30]));
+        assert_eq!(mean_squared_error(&image, &image), 0.0);
+    }
+
+    #[test]
+    fn mean_squared_error_of_known_pair() {
+        let a = image::ImageBuffer::from_pixel(1, 1, image::Rgb([0, 0, 0]));
+        let b = image::ImageBuffer::from_pixel(1, 1, image::Rgb([10, 10, 10]));
+        assert_eq!(mean_squared_error(&a, &b), 100.0);
+    }
+
+    #[test]
+    fn mse_similarity_of_zero_mse_is_one() {
+        assert_eq!(mse_similarity(0.0), 1.0);
+    }
+
+    #[test]
+    fn mse_similarity_of_max_mse_is_zero() {
+        assert_eq!(mse_similarity(MSE_NORMALIZATION), 0.0);
+    }
+
+    #[test]
+    fn psnr_similarity_of_identical_images_is_one() {
+        assert_eq!(psnr_similarity(0.0), 1.0);
+    }
+
+    #[test]
+    fn psnr_similarity_of_known_mse() {
+        // mse = 100.0 -> psnr = 20*log10(255) - 10*log10(100) ~= 28.13dB,
+        // clamped into 0.0..=1.0 against PSNR_NORMALIZATION.
+        let expected = (20.0 * 255f64.log10() - 10.0 * 100f64.log10()) / PSNR_NORMALIZATION;
+        assert_eq!(psnr_similarity(100.0), expected.clamp(0.0, 1.0));
+    }
+}